@@ -0,0 +1,132 @@
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Dithering strategy applied when quantizing to 8- or 16-bit integer PCM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// Truncate with no dither, as before.
+    Off,
+    /// Triangular-PDF dither plus first-order error-feedback noise shaping.
+    Tpdf,
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Dither::Off),
+            "tpdf" => Ok(Dither::Tpdf),
+            _ => Err(format!("Expected \"tpdf\" or \"off\" (found \"{s}\")")),
+        }
+    }
+}
+
+/// A small, fast xorshift64 PRNG. Not cryptographically secure; only used to
+/// generate dither noise.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    /// Returns a uniform value in `[0, 1)`.
+    fn next_unit(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Per-channel state for TPDF dither with first-order error-feedback noise shaping,
+/// threaded across the whole sample loop so the shaping carries over between
+/// rendered blocks.
+pub struct DitherState {
+    rng: Rng,
+    /// Accumulated quantization error, indexed by channel (0 = left, 1 = right).
+    error: [f64; 2],
+}
+
+impl DitherState {
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock before unix epoch")
+            .as_nanos() as u64;
+        Self {
+            rng: Rng::new(seed),
+            error: [0.0; 2],
+        }
+    }
+
+    /// Quantizes `value`, which is already scaled to the target integer range (one
+    /// LSB = `1.0`), to the nearest integer for `channel`. Adds triangular-PDF dither
+    /// of one LSB and feeds the previous quantization error back into the signal
+    /// before rounding, pushing noise toward higher frequencies.
+    pub fn quantize(&mut self, channel: usize, value: f64) -> f64 {
+        let shaped = value - self.error[channel];
+        let dither = self.rng.next_unit() - self.rng.next_unit(); // triangular in (-1, 1)
+        let quantized = (shaped + dither).round();
+        self.error[channel] = quantized - shaped;
+        quantized
+    }
+}
+
+impl Default for DitherState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_with_seed(seed: u64) -> DitherState {
+        DitherState {
+            rng: Rng::new(seed),
+            error: [0.0; 2],
+        }
+    }
+
+    #[test]
+    fn quantize_output_is_near_integer() {
+        let mut state = state_with_seed(1);
+        for _ in 0..100 {
+            let quantized = state.quantize(0, 2.3);
+            assert!((quantized - quantized.round()).abs() < f64::EPSILON);
+        }
+    }
+
+    #[test]
+    fn quantize_is_unbiased_on_average() {
+        let mut state = state_with_seed(42);
+        let sum: f64 = (0..10_000).map(|_| state.quantize(0, 2.3)).sum();
+        let mean = sum / 10_000.0;
+        assert!((mean - 2.3).abs() < 0.01, "mean {mean} not close to 2.3");
+    }
+
+    #[test]
+    fn quantize_error_feedback_stays_bounded() {
+        let mut state = state_with_seed(7);
+        for _ in 0..10_000 {
+            state.quantize(0, 2.3);
+            assert!(state.error[0].abs() <= 1.5, "error {} blew up", state.error[0]);
+        }
+    }
+
+    #[test]
+    fn quantize_channels_track_separate_error_state() {
+        let mut state = state_with_seed(99);
+        let left = state.quantize(0, 5.5);
+        let right = state.quantize(1, -5.5);
+        assert!((5.0..=6.0).contains(&left));
+        assert!((-6.0..=-5.0).contains(&right));
+        assert!(state.error[0].abs() <= 1.5);
+        assert!(state.error[1].abs() <= 1.5);
+    }
+}
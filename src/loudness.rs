@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+
+/// Block length and hop for the BS.1770 gating window (400 ms blocks, 75% overlap).
+const BLOCK_SECONDS: f64 = 0.4;
+const BLOCK_OVERLAP: f64 = 0.75;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_OFFSET_LU: f64 = -10.0;
+
+/// A single biquad section in transposed direct form II.
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// The ITU-R BS.1770 K-weighting pre-filter: a high-shelf boosting everything above
+/// ~1.5 kHz, followed by a ~38 Hz high-pass (the "RLB" stage) approximating the
+/// head/ear response.
+struct KWeightingFilter {
+    shelf: Biquad,
+    highpass: Biquad,
+}
+
+impl KWeightingFilter {
+    fn new(sample_rate: u32) -> Self {
+        let fs = sample_rate as f64;
+
+        // Stage 1: high shelf, ~+4 dB above 1.5 kHz.
+        let f0 = 1_681.974_450_955_532;
+        let gain_db = 3.999_843_853_97;
+        let q = 0.707_175_236_955_419_6;
+        let k = (std::f64::consts::PI * f0 / fs).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.499_666_774_155);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        );
+
+        // Stage 2: high-pass, ~38 Hz.
+        let f0 = 38.135_470_876_139_82;
+        let q = 0.500_327_037_325_395_3;
+        let k = (std::f64::consts::PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let highpass = Biquad::new(
+            1.0,
+            -2.0,
+            1.0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        );
+
+        Self { shelf, highpass }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.highpass.process(self.shelf.process(x))
+    }
+}
+
+/// Measures ITU-R BS.1770 integrated loudness over a stream of stereo samples,
+/// without buffering the whole song: only the current 400 ms gating window and the
+/// per-block mean squares are kept.
+pub struct LoudnessMeter {
+    filter_l: KWeightingFilter,
+    filter_r: KWeightingFilter,
+    block_frames: usize,
+    step_frames: usize,
+    window_l: VecDeque<f64>,
+    window_r: VecDeque<f64>,
+    sum_sq_l: f64,
+    sum_sq_r: f64,
+    frames_until_next_block: usize,
+    /// Mean square energy (summed across channels) of each 400 ms block seen so far.
+    blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32) -> Self {
+        let block_frames = (sample_rate as f64 * BLOCK_SECONDS).round() as usize;
+        let step_frames = (block_frames as f64 * (1.0 - BLOCK_OVERLAP)).round() as usize;
+        Self {
+            filter_l: KWeightingFilter::new(sample_rate),
+            filter_r: KWeightingFilter::new(sample_rate),
+            block_frames,
+            step_frames: step_frames.max(1),
+            window_l: VecDeque::with_capacity(block_frames),
+            window_r: VecDeque::with_capacity(block_frames),
+            sum_sq_l: 0.0,
+            sum_sq_r: 0.0,
+            // Only counted down once the window is full, so the first block is taken
+            // as soon as the first 400 ms have been seen.
+            frames_until_next_block: 1,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Feeds one stereo frame into the meter.
+    pub fn push(&mut self, left: f64, right: f64) {
+        let l = self.filter_l.process(left);
+        let r = self.filter_r.process(right);
+
+        self.window_l.push_back(l * l);
+        self.sum_sq_l += l * l;
+        self.window_r.push_back(r * r);
+        self.sum_sq_r += r * r;
+
+        if self.window_l.len() > self.block_frames {
+            self.sum_sq_l -= self.window_l.pop_front().unwrap();
+            self.sum_sq_r -= self.window_r.pop_front().unwrap();
+        }
+
+        if self.window_l.len() == self.block_frames {
+            self.frames_until_next_block -= 1;
+            if self.frames_until_next_block == 0 {
+                self.frames_until_next_block = self.step_frames;
+                let z = self.sum_sq_l / self.block_frames as f64
+                    + self.sum_sq_r / self.block_frames as f64;
+                self.blocks.push(z);
+            }
+        }
+    }
+
+    /// The gated integrated loudness in LUFS, per ITU-R BS.1770-4: blocks below
+    /// -70 LUFS are dropped, then blocks below (average - 10 LU) are dropped, and the
+    /// remaining blocks' energy is averaged.
+    pub fn integrated_loudness(&self) -> f64 {
+        let to_lufs = |z: f64| -0.691 + 10.0 * z.log10();
+
+        let absolute_gated: Vec<f64> = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|&z| z > 0.0 && to_lufs(z) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if absolute_gated.is_empty() {
+            return ABSOLUTE_GATE_LUFS;
+        }
+
+        let mean_z = absolute_gated.iter().sum::<f64>() / absolute_gated.len() as f64;
+        let relative_threshold = to_lufs(mean_z) + RELATIVE_GATE_OFFSET_LU;
+
+        let relative_gated: Vec<f64> = absolute_gated
+            .into_iter()
+            .filter(|&z| to_lufs(z) > relative_threshold)
+            .collect();
+        if relative_gated.is_empty() {
+            return to_lufs(mean_z);
+        }
+
+        let mean_z = relative_gated.iter().sum::<f64>() / relative_gated.len() as f64;
+        to_lufs(mean_z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `seconds` of a sine wave of `amplitude` at `freq_hz` through a fresh meter
+    /// and returns its integrated loudness.
+    fn sine_loudness(sample_rate: u32, freq_hz: f64, amplitude: f64, seconds: f64) -> f64 {
+        let mut meter = LoudnessMeter::new(sample_rate);
+        let frames = (sample_rate as f64 * seconds) as usize;
+        for n in 0..frames {
+            let t = n as f64 / sample_rate as f64;
+            let sample = amplitude * (2.0 * std::f64::consts::PI * freq_hz * t).sin();
+            meter.push(sample, sample);
+        }
+        meter.integrated_loudness()
+    }
+
+    #[test]
+    fn silence_reads_the_absolute_gate_floor() {
+        let mut meter = LoudnessMeter::new(48_000);
+        for _ in 0..(48_000 * 2) {
+            meter.push(0.0, 0.0);
+        }
+        assert_eq!(meter.integrated_loudness(), ABSOLUTE_GATE_LUFS);
+    }
+
+    #[test]
+    fn halving_amplitude_drops_loudness_by_about_6_db() {
+        let full = sine_loudness(48_000, 997.0, 1.0, 2.0);
+        let half = sine_loudness(48_000, 997.0, 0.5, 2.0);
+        let delta = full - half;
+        assert!((delta - 6.02).abs() < 0.2, "expected ~6 dB drop, got {delta}");
+    }
+
+    #[test]
+    fn louder_tone_measures_louder() {
+        let quiet = sine_loudness(48_000, 997.0, 0.1, 2.0);
+        let loud = sine_loudness(48_000, 997.0, 0.9, 2.0);
+        assert!(loud > quiet);
+    }
+}
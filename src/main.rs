@@ -1,8 +1,33 @@
-use std::{fs::File, path::PathBuf, sync::Arc};
+mod dither;
+mod loudness;
+mod normalize;
+mod playback;
+mod render;
+mod resample;
+mod wav;
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use argh::FromArgs;
 use rustysynth::{MidiFile, MidiFileSequencer, SoundFont, Synthesizer, SynthesizerSettings};
 
+use crate::dither::{Dither, DitherState};
+use crate::normalize::Normalize;
+use crate::resample::Interp;
+use crate::wav::{SampleFormat, WavMetadata};
+
+const CHANNEL_COUNT: i32 = 16;
+
+/// MIDI Control Change command, used to silence muted channels.
+const CONTROL_CHANGE: i32 = 0xB0;
+/// Channel Volume controller number (CC#7).
+const CHANNEL_VOLUME: i32 = 7;
+
 #[derive(FromArgs)]
 /// Render MIDI files to .wav files
 struct CliArgs {
@@ -22,18 +47,95 @@ struct CliArgs {
     #[argh(option, short = 'r', default = "48000")]
     sample_rate: u32,
 
-    /// the bit depth of the output file (one of 8, 16, 24 or 32)
+    /// the bit depth of the output file (8, 16, 24 or 32 for pcm; 32 or 64 for float)
     #[argh(option, short = 'd', default = "24")]
     bit_depth: u16,
+
+    /// the sample format of the output file (one of "pcm", "float")
+    #[argh(option, default = "SampleFormat::Pcm")]
+    format: SampleFormat,
+
+    /// render each of the 16 MIDI channels to its own `<input>.chNN.wav` file instead
+    /// of a single mix
+    #[argh(switch)]
+    stems: bool,
+
+    /// stream the rendered audio to the default output device instead of writing a
+    /// .wav file
+    #[argh(switch)]
+    play: bool,
+
+    /// dither applied when quantizing to 8- or 16-bit PCM (one of "tpdf", "off")
+    #[argh(option, default = "Dither::Tpdf")]
+    dither: Dither,
+
+    /// scale the output to a target level before encoding (one of "off", "peak", "lufs")
+    #[argh(option, default = "Normalize::Off")]
+    normalize: Normalize,
+
+    /// target level for `--normalize` (dBFS for peak, LUFS for lufs); defaults to
+    /// -1 dBFS for peak and -14 LUFS for lufs
+    #[argh(option)]
+    normalize_target: Option<f64>,
+
+    /// render internally at this rate and resample to `--sample-rate`; defaults to
+    /// `--sample-rate` (no resampling)
+    #[argh(option)]
+    internal_rate: Option<u32>,
+
+    /// interpolation used when `--internal-rate` differs from `--sample-rate` (one of
+    /// "nearest", "linear", "cosine", "cubic")
+    #[argh(option, default = "Interp::Linear")]
+    interp: Interp,
+
+    /// artist tag embedded in the output file's `LIST`/`INFO` chunk (`IART`)
+    #[argh(option)]
+    artist: Option<String>,
+
+    /// comment tag embedded in the output file's `LIST`/`INFO` chunk (`ICMT`)
+    #[argh(option)]
+    comment: Option<String>,
+}
+
+fn dither_state(dither: Dither, format: SampleFormat, bit_depth: u16) -> Option<DitherState> {
+    let applies =
+        dither == Dither::Tpdf && format == SampleFormat::Pcm && matches!(bit_depth, 8 | 16);
+    applies.then(DitherState::new)
+}
+
+/// Builds a sequencer that plays `midi_file` through `sound_font` at `sample_rate`,
+/// with every channel in `mute_channels` silenced from the start by zeroing its
+/// Channel Volume before the sequencer takes ownership of the synth.
+///
+/// This only sets the synth's *initial* state: `rustysynth` only exposes
+/// `process_midi_message` on `Synthesizer`, and once it's handed to
+/// `MidiFileSequencer::new` there's no way to reach it mutably again. So if the MIDI
+/// file carries its own Channel Volume (CC7) event for a muted channel later in the
+/// track, that channel will un-mute itself for the rest of the song. This is a known
+/// limitation of muting this way; see [`render_stems`].
+fn build_sequencer(
+    midi_file: &Arc<MidiFile>,
+    sound_font: &Arc<SoundFont>,
+    sample_rate: u32,
+    mute_channels: &[i32],
+) -> MidiFileSequencer {
+    let settings = SynthesizerSettings::new(sample_rate as i32);
+    let mut synth = Synthesizer::new(sound_font, &settings).expect("create synth");
+    for &channel in mute_channels {
+        synth.process_midi_message(channel, CONTROL_CHANGE, CHANNEL_VOLUME, 0);
+    }
+    let mut sequencer = MidiFileSequencer::new(synth);
+    sequencer.play(midi_file, false);
+    sequencer
 }
 
 fn main() {
     let args = argh::from_env::<CliArgs>();
 
-    if !matches!(args.bit_depth, 8 | 16 | 24 | 32) {
+    if !args.format.supports_bit_depth(args.bit_depth) {
         panic!(
-            "Expected bit depth to be 8, 16, 24 or 32 (found {})",
-            args.bit_depth
+            "Unsupported bit depth {} for format {:?}",
+            args.bit_depth, args.format
         );
     }
 
@@ -51,108 +153,238 @@ fn main() {
     };
     let sound_font = Arc::new(sound_font);
 
-    println!("Initializing synth");
-    let settings = SynthesizerSettings::new(args.sample_rate as i32);
-    let synth = Synthesizer::new(&sound_font, &settings).expect("create synth");
-
-    println!("Initializing sequencer");
-    let mut sequencer = MidiFileSequencer::new(synth);
-    sequencer.play(&midi_file, false);
+    if args.stems {
+        render_stems(&args, &midi_file, &sound_font);
+        return;
+    }
 
-    let sample_count = (settings.sample_rate as f64 * midi_file.get_length()) as usize;
-    let mut left: Vec<f32> = vec![0_f32; sample_count];
-    let mut right: Vec<f32> = vec![0_f32; sample_count];
+    if args.play {
+        println!("Initializing synth");
+        println!("Initializing sequencer");
+        let sequencer = build_sequencer(&midi_file, &sound_font, args.sample_rate, &[]);
+        println!("Playing back through the default output device");
+        playback::play(sequencer, args.sample_rate, midi_file.get_length());
+        return;
+    }
 
-    println!("Rendering to buffer");
-    sequencer.render(&mut left[..], &mut right[..]);
+    let internal_rate = args.internal_rate.unwrap_or(args.sample_rate);
+    println!("Initializing synth");
+    println!("Initializing sequencer");
+    let mut sequencer = build_sequencer(&midi_file, &sound_font, internal_rate, &[]);
 
-    println!("Wrapping in wav container");
-    let rendered = wrap_as_wav(
-        left.into_iter().zip(right),
-        settings.sample_rate as u32,
-        args.bit_depth,
+    let gain_analysis_frames = (internal_rate as f64 * midi_file.get_length()) as u64;
+    let gain = normalization_gain(
+        &args,
+        &midi_file,
+        &sound_font,
+        internal_rate,
+        gain_analysis_frames,
+        &[],
     );
 
-    let output = args.output_file.unwrap_or_else(|| {
+    let output = args.output_file.clone().unwrap_or_else(|| {
         let mut path = args.midi_file.clone();
         path.set_extension("wav");
         path
     });
 
-    std::fs::write(output, rendered).expect("write output file");
+    println!("Rendering to {}", output.display());
+    render_song(
+        &args,
+        &mut sequencer,
+        internal_rate,
+        midi_file.get_length(),
+        gain,
+        &output,
+        &wav_metadata(&args, midi_title(&args.midi_file)),
+    );
+}
+
+/// The default `INAM` title for a render: the MIDI file's name without extension.
+fn midi_title(midi_file: &Path) -> String {
+    midi_file
+        .file_stem()
+        .expect("midi file has no file name")
+        .to_string_lossy()
+        .into_owned()
 }
 
-pub fn wrap_as_wav(
-    samples: impl Iterator<Item = (f32, f32)> + Clone,
+fn wav_metadata(args: &CliArgs, title: String) -> WavMetadata {
+    WavMetadata {
+        title: Some(title),
+        artist: args.artist.clone(),
+        comment: args.comment.clone(),
+    }
+}
+
+/// Renders `sequencer` (already playing, at `internal_rate`) to `output`, resampling
+/// to `args.sample_rate` first if `internal_rate` differs. Returns `true` if any
+/// non-silent sample was produced.
+#[allow(clippy::too_many_arguments)]
+fn render_song(
+    args: &CliArgs,
+    sequencer: &mut MidiFileSequencer,
+    internal_rate: u32,
+    song_length: f64,
+    gain: f32,
+    output: &Path,
+    metadata: &WavMetadata,
+) -> bool {
+    let source_frames = (internal_rate as f64 * song_length) as u64;
+    let mut writer = BufWriter::new(File::create(output).expect("create output file"));
+    let mut dither = dither_state(args.dither, args.format, args.bit_depth);
+
+    let has_audio = if internal_rate == args.sample_rate {
+        wav::write_wav_header(
+            &mut writer,
+            args.sample_rate,
+            args.format,
+            args.bit_depth,
+            source_frames,
+            metadata,
+        )
+        .expect("write wav header");
+
+        render::render_blocks(
+            sequencer,
+            args.format,
+            args.bit_depth,
+            source_frames,
+            &mut writer,
+            dither.as_mut(),
+            gain,
+        )
+        .expect("encode samples")
+    } else {
+        let output_frames =
+            (source_frames as f64 * args.sample_rate as f64 / internal_rate as f64).round() as u64;
+        wav::write_wav_header(
+            &mut writer,
+            args.sample_rate,
+            args.format,
+            args.bit_depth,
+            output_frames,
+            metadata,
+        )
+        .expect("write wav header");
+
+        render::render_resampled_blocks(
+            sequencer,
+            args.format,
+            args.bit_depth,
+            internal_rate,
+            args.sample_rate,
+            args.interp,
+            source_frames,
+            output_frames,
+            &mut writer,
+            dither.as_mut(),
+            gain,
+        )
+        .expect("encode resampled samples")
+    };
+
+    writer.flush().expect("flush output file");
+    has_audio
+}
+
+/// Runs the `--normalize` analysis pass, if enabled, and returns the linear gain to
+/// apply while encoding. `mute_channels` must match whatever muting the real render
+/// will apply (see [`render_stems`]), so the analysis measures the same signal that
+/// ends up on disk.
+fn normalization_gain(
+    args: &CliArgs,
+    midi_file: &Arc<MidiFile>,
+    sound_font: &Arc<SoundFont>,
     sample_rate: u32,
-    bit_depth: u16,
-) -> Vec<u8> {
-    // See: http://soundfile.sapp.org/doc/WaveFormat/
-
-    debug_assert_eq!(bit_depth % 8, 0, "Bit depth must be a multiple of 8");
-    let byte_depth = bit_depth / 8;
-
-    let mut out = vec![];
-
-    let sample_count = samples.clone().count() as u32;
-    let expected_data_length = sample_count * 2 * byte_depth as u32;
-
-    // RIFF header
-    out.extend(b"RIFF"); // ChunkID
-    out.extend((36 + expected_data_length).to_le_bytes()); // ChunkSize
-    out.extend(b"WAVE"); // Format
-    debug_assert_eq!(12, out.len(), "length mismatch after header");
-
-    // subchunk 1: 'fmt '
-    out.extend(b"fmt "); // Subchunk1ID
-    out.extend(16u32.to_le_bytes()); // Subchunk1Size
-    out.extend(1u16.to_le_bytes()); // AudioFormat (1 = PCM)
-    out.extend(2u16.to_le_bytes()); // NumChannels (2 for stereo)
-    out.extend(sample_rate.to_le_bytes()); // SampleRate
-    out.extend((sample_rate * 2 * byte_depth as u32).to_le_bytes()); // ByteRate, SampleRate * NumChannels * ByteDepth
-    out.extend((2u16 * byte_depth).to_le_bytes()); // BlockAlign, NumChannels * ByteDepth
-    out.extend(bit_depth.to_le_bytes()); // BitsPerSample
-    // extra parameters would go here if not PCM
-    debug_assert_eq!(36, out.len(), "length mismatch after subchunk 1");
-
-    // subchunk 2: 'data'
-    out.extend(b"data");
-    out.extend(expected_data_length.to_le_bytes());
-    for (l, r) in samples {
-        // convert to 64-bit float to ensure no accuracy loss
-        let (l, r) = (l as f64, r as f64);
-        match bit_depth {
-            8 => {
-                let (l, r) = ((l + 1.) / 2., (r + 1.) / 2.);
-                out.extend(((l * 256.) as u8).to_le_bytes());
-                out.extend(((r * 256.) as u8).to_le_bytes());
-            }
-            16 => {
-                out.extend(((l * 32_767.) as i16).to_le_bytes());
-                out.extend(((r * 32_767.) as i16).to_le_bytes());
-            }
-            24 => {
-                let convert = |num: i32| {
-                    let bytes = num.to_le_bytes();
-                    let fixed_byte_2 = (bytes[2] & 0b0111_1111) | (bytes[3] & 0b1000_0000);
-                    [bytes[0], bytes[1], fixed_byte_2]
-                };
-
-                out.extend(convert((l * 8_388_607.) as i32));
-                out.extend(convert((r * 8_388_607.) as i32));
-            }
-            32 => {
-                out.extend(((l * 2_147_483_647.) as i32).to_le_bytes());
-                out.extend(((r * 2_147_483_647.) as i32).to_le_bytes());
-            }
-            _ => unreachable!("Unexpected bit depth {bit_depth}, expected 8, 16, 24 or 32"),
-        };
+    total_frames: u64,
+    mute_channels: &[i32],
+) -> f32 {
+    if args.normalize == Normalize::Off {
+        return 1.0;
     }
-    debug_assert_eq!(
-        44 + expected_data_length as usize,
-        out.len(),
-        "length mismatch after subchunk 2"
-    );
 
-    out
+    println!("Analyzing {:?} level", args.normalize);
+    let target_db = args
+        .normalize_target
+        .unwrap_or_else(|| args.normalize.default_target_db());
+    normalize::gain(
+        args.normalize,
+        target_db,
+        midi_file,
+        sound_font,
+        sample_rate,
+        total_frames,
+        mute_channels,
+    )
+}
+
+/// Renders each of the 16 MIDI channels to its own `<input>.chNN.wav` file, muting
+/// every other channel by zeroing its channel volume before the sequencer starts
+/// playing. This only fixes the synth's initial state: if the MIDI file carries its
+/// own Channel Volume (CC7) event for a muted channel later in the track, that
+/// channel can un-mute itself partway through the song (see [`build_sequencer`]).
+/// Channels that produce no audible output are skipped (their file is removed after
+/// rendering).
+fn render_stems(args: &CliArgs, midi_file: &Arc<MidiFile>, sound_font: &Arc<SoundFont>) {
+    let internal_rate = args.internal_rate.unwrap_or(args.sample_rate);
+
+    for channel in 0..CHANNEL_COUNT {
+        println!("Rendering channel {} of {CHANNEL_COUNT}", channel + 1);
+
+        let muted_channels: Vec<i32> = (0..CHANNEL_COUNT).filter(|&c| c != channel).collect();
+        let mut sequencer = build_sequencer(midi_file, sound_font, internal_rate, &muted_channels);
+
+        let gain_analysis_frames = (internal_rate as f64 * midi_file.get_length()) as u64;
+        let gain = normalization_gain(
+            args,
+            midi_file,
+            sound_font,
+            internal_rate,
+            gain_analysis_frames,
+            &muted_channels,
+        );
+
+        let output = stem_output_path(&args.midi_file, channel);
+        let title = format!("{} - Channel {:02}", midi_title(&args.midi_file), channel + 1);
+        let has_audio = render_song(
+            args,
+            &mut sequencer,
+            internal_rate,
+            midi_file.get_length(),
+            gain,
+            &output,
+            &wav_metadata(args, title),
+        );
+
+        if !has_audio {
+            std::fs::remove_file(&output).expect("remove silent stem file");
+        }
+    }
+}
+
+fn stem_output_path(midi_file: &Path, channel: i32) -> PathBuf {
+    let mut path = midi_file.to_owned();
+    path.set_file_name(format!("{}.ch{:02}.wav", midi_title(midi_file), channel + 1));
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn midi_title_strips_the_directory_and_extension() {
+        let title = midi_title(Path::new("/home/user/Songs/Für Elise.mid"));
+        assert_eq!(title, "Für Elise");
+    }
+
+    #[test]
+    fn stem_output_path_pads_the_channel_number_and_keeps_the_directory() {
+        let path = stem_output_path(Path::new("/home/user/Songs/song.mid"), 0);
+        assert_eq!(path, Path::new("/home/user/Songs/song.ch01.wav"));
+
+        let path = stem_output_path(Path::new("song.mid"), 15);
+        assert_eq!(path, Path::new("song.ch16.wav"));
+    }
 }
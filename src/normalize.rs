@@ -0,0 +1,108 @@
+use std::{str::FromStr, sync::Arc};
+
+use rustysynth::{MidiFile, MidiFileSequencer, SoundFont, Synthesizer, SynthesizerSettings};
+
+use crate::loudness::LoudnessMeter;
+use crate::render;
+
+/// Loudness normalization strategy applied before encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalize {
+    Off,
+    /// Scale so the maximum absolute sample hits a target ceiling (default -1 dBFS).
+    Peak,
+    /// Scale so the ITU-R BS.1770 integrated loudness hits a target (default -14 LUFS).
+    Lufs,
+}
+
+impl Normalize {
+    pub fn default_target_db(self) -> f64 {
+        match self {
+            Normalize::Off => 0.0,
+            Normalize::Peak => -1.0,
+            Normalize::Lufs => -14.0,
+        }
+    }
+}
+
+impl FromStr for Normalize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Normalize::Off),
+            "peak" => Ok(Normalize::Peak),
+            "lufs" => Ok(Normalize::Lufs),
+            _ => Err(format!("Expected \"off\", \"peak\" or \"lufs\" (found \"{s}\")")),
+        }
+    }
+}
+
+/// Runs a first analysis pass over the whole song and returns the linear gain to
+/// apply before encoding so the output hits `target_db` (dBFS for peak, LUFS for
+/// lufs). Renders with its own synth/sequencer so the caller's sequencer is left
+/// untouched for the real encode pass. `mute_channels` is muted on the analysis
+/// synth the same way it is on the real render's (see
+/// [`crate::build_sequencer`]), so a `--stems` analysis pass measures the level of
+/// the isolated channel rather than the full mix.
+pub fn gain(
+    mode: Normalize,
+    target_db: f64,
+    midi_file: &Arc<MidiFile>,
+    sound_font: &Arc<SoundFont>,
+    sample_rate: u32,
+    total_frames: u64,
+    mute_channels: &[i32],
+) -> f32 {
+    match mode {
+        Normalize::Off => 1.0,
+        Normalize::Peak => {
+            let mut sequencer = analysis_sequencer(midi_file, sound_font, sample_rate, mute_channels);
+            let mut peak = 0_f32;
+            render::for_each_block(&mut sequencer, total_frames, |left, right| {
+                peak = left
+                    .iter()
+                    .chain(right)
+                    .fold(peak, |acc, &sample| acc.max(sample.abs()));
+            });
+
+            if peak <= 0.0 {
+                1.0
+            } else {
+                (10f64.powf(target_db / 20.0) / peak as f64) as f32
+            }
+        }
+        Normalize::Lufs => {
+            let mut sequencer = analysis_sequencer(midi_file, sound_font, sample_rate, mute_channels);
+            let mut meter = LoudnessMeter::new(sample_rate);
+            render::for_each_block(&mut sequencer, total_frames, |left, right| {
+                for (&l, &r) in left.iter().zip(right) {
+                    meter.push(l as f64, r as f64);
+                }
+            });
+
+            10f64.powf((target_db - meter.integrated_loudness()) / 20.0) as f32
+        }
+    }
+}
+
+/// MIDI Control Change command, used to silence muted channels.
+const CONTROL_CHANGE: i32 = 0xB0;
+/// Channel Volume controller number (CC#7).
+const CHANNEL_VOLUME: i32 = 7;
+
+fn analysis_sequencer(
+    midi_file: &Arc<MidiFile>,
+    sound_font: &Arc<SoundFont>,
+    sample_rate: u32,
+    mute_channels: &[i32],
+) -> MidiFileSequencer {
+    let settings = SynthesizerSettings::new(sample_rate as i32);
+    let mut synth = Synthesizer::new(sound_font, &settings).expect("create synth");
+    for &channel in mute_channels {
+        synth.process_midi_message(channel, CONTROL_CHANGE, CHANNEL_VOLUME, 0);
+    }
+    let mut sequencer = MidiFileSequencer::new(synth);
+    sequencer.play(midi_file, false);
+    sequencer
+}
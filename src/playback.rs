@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleRate, StreamConfig};
+use rustysynth::MidiFileSequencer;
+
+use crate::render::BLOCK_FRAMES;
+
+/// Extra time to let reverb/release tails ring out after the song itself ends.
+const REVERB_TAIL_SECONDS: f64 = 2.0;
+
+/// Streams `sequencer` to the default output device in real time, stopping
+/// `song_length_seconds` plus a short reverb tail after playback starts.
+pub fn play(mut sequencer: MidiFileSequencer, sample_rate: u32, song_length_seconds: f64) {
+    let host = cpal::default_host();
+    let device = host
+        .default_output_device()
+        .expect("no default output device available");
+
+    let config = StreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+
+    let mut left = vec![0_f32; BLOCK_FRAMES];
+    let mut right = vec![0_f32; BLOCK_FRAMES];
+    let mut cursor = 0_usize;
+    let mut filled = 0_usize;
+    let mut frames_remaining =
+        (sample_rate as f64 * (song_length_seconds + REVERB_TAIL_SECONDS)) as u64;
+
+    let stream = device
+        .build_output_stream(
+            &config,
+            move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let mut written = 0;
+                while written < output.len() {
+                    if cursor == filled {
+                        if frames_remaining == 0 {
+                            output[written..].fill(0.0);
+                            return;
+                        }
+
+                        let block_frames = frames_remaining.min(BLOCK_FRAMES as u64) as usize;
+                        sequencer.render(&mut left[..block_frames], &mut right[..block_frames]);
+                        filled = block_frames;
+                        cursor = 0;
+                        frames_remaining -= block_frames as u64;
+                    }
+
+                    output[written] = left[cursor];
+                    output[written + 1] = right[cursor];
+                    written += 2;
+                    cursor += 1;
+                }
+            },
+            |err| eprintln!("playback stream error: {err}"),
+            None,
+        )
+        .expect("build output stream");
+
+    stream.play().expect("start playback stream");
+
+    std::thread::sleep(Duration::from_secs_f64(
+        song_length_seconds + REVERB_TAIL_SECONDS,
+    ));
+}
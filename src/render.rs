@@ -0,0 +1,127 @@
+use std::io::{self, Write};
+
+use rustysynth::MidiFileSequencer;
+
+use crate::dither::DitherState;
+use crate::resample::{Interp, Resampler};
+use crate::wav::{self, SampleFormat};
+
+/// Number of frames rendered and encoded per chunk, keeping peak memory constant
+/// regardless of song length.
+pub const BLOCK_FRAMES: usize = 4096;
+
+/// Renders `sequencer` in fixed-size blocks, invoking `callback` with the
+/// deinterleaved left/right samples of each block. Used both for encoding and for
+/// analysis passes (peak/loudness) that only need to look at the samples once.
+pub fn for_each_block(
+    sequencer: &mut MidiFileSequencer,
+    total_frames: u64,
+    mut callback: impl FnMut(&[f32], &[f32]),
+) {
+    let mut left = vec![0_f32; BLOCK_FRAMES];
+    let mut right = vec![0_f32; BLOCK_FRAMES];
+    let mut frames_remaining = total_frames;
+
+    while frames_remaining > 0 {
+        let block_frames = frames_remaining.min(BLOCK_FRAMES as u64) as usize;
+        sequencer.render(&mut left[..block_frames], &mut right[..block_frames]);
+        callback(&left[..block_frames], &right[..block_frames]);
+        frames_remaining -= block_frames as u64;
+    }
+}
+
+/// Renders `sequencer` in fixed-size blocks, applies `gain`, and encodes each block
+/// straight to `writer`, which must already hold a WAV header sized for
+/// `total_frames`. Returns `true` if any non-silent sample was produced.
+#[allow(clippy::too_many_arguments)]
+pub fn render_blocks(
+    sequencer: &mut MidiFileSequencer,
+    format: SampleFormat,
+    bit_depth: u16,
+    total_frames: u64,
+    writer: &mut impl Write,
+    mut dither: Option<&mut DitherState>,
+    gain: f32,
+) -> io::Result<bool> {
+    let mut has_audio = false;
+    let mut result = Ok(());
+
+    for_each_block(sequencer, total_frames, |left, right| {
+        if result.is_err() {
+            return;
+        }
+
+        if !has_audio {
+            has_audio = left.iter().chain(right).any(|&sample| sample != 0.0);
+        }
+
+        result = wav::encode_samples(
+            writer,
+            left.iter()
+                .copied()
+                .zip(right.iter().copied())
+                .map(|(l, r)| (l * gain, r * gain)),
+            format,
+            bit_depth,
+            dither.as_deref_mut(),
+        );
+    });
+
+    result.map(|()| has_audio)
+}
+
+/// Renders `sequencer` at `source_rate` in fixed-size blocks, resamples to
+/// `target_rate` with `interp`, applies `gain`, and encodes the result straight to
+/// `writer`, which must already hold a WAV header sized for `output_frames_total`.
+/// Returns `true` if any non-silent sample was produced.
+#[allow(clippy::too_many_arguments)]
+pub fn render_resampled_blocks(
+    sequencer: &mut MidiFileSequencer,
+    format: SampleFormat,
+    bit_depth: u16,
+    source_rate: u32,
+    target_rate: u32,
+    interp: Interp,
+    source_frames_total: u64,
+    output_frames_total: u64,
+    writer: &mut impl Write,
+    mut dither: Option<&mut DitherState>,
+    gain: f32,
+) -> io::Result<bool> {
+    let mut resampler = Resampler::new(interp, source_rate, target_rate, output_frames_total);
+    let mut has_audio = false;
+    let mut result = Ok(());
+
+    for_each_block(sequencer, source_frames_total, |left, right| {
+        if result.is_err() {
+            return;
+        }
+
+        let resampled = resampler.push_block(left, right);
+        if !has_audio {
+            has_audio = resampled.iter().any(|&(l, r)| l != 0.0 || r != 0.0);
+        }
+        result = wav::encode_samples(
+            writer,
+            resampled.into_iter().map(|(l, r)| (l * gain, r * gain)),
+            format,
+            bit_depth,
+            dither.as_deref_mut(),
+        );
+    });
+    result?;
+
+    let tail = resampler.finish();
+    if !has_audio {
+        has_audio = tail.iter().any(|&(l, r)| l != 0.0 || r != 0.0);
+    }
+    wav::encode_samples(
+        writer,
+        tail.into_iter().map(|(l, r)| (l * gain, r * gain)),
+        format,
+        bit_depth,
+        dither,
+    )?;
+
+    Ok(has_audio)
+}
@@ -0,0 +1,223 @@
+use std::{collections::VecDeque, str::FromStr};
+
+/// Interpolation method used to resample between an internal render rate and the
+/// output sample rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interp {
+    Nearest,
+    Linear,
+    Cosine,
+    Cubic,
+}
+
+impl FromStr for Interp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Interp::Nearest),
+            "linear" => Ok(Interp::Linear),
+            "cosine" => Ok(Interp::Cosine),
+            "cubic" => Ok(Interp::Cubic),
+            _ => Err(format!(
+                "Expected \"nearest\", \"linear\", \"cosine\" or \"cubic\" (found \"{s}\")"
+            )),
+        }
+    }
+}
+
+impl Interp {
+    /// Interpolates at fraction `t` in `[0, 1)` between `x1` (source index `i`) and
+    /// `x2` (index `i+1`), using the neighboring `x0`/`x3` (indices `i-1`/`i+2`) for
+    /// methods that need extra support.
+    ///
+    /// `Cosine` and `Cubic` are clamped to `[min(x1, x2), max(x1, x2)]`: Catmull-Rom
+    /// in particular can overshoot well past its four input samples (e.g. a
+    /// `(-1, 1, 1, -1)` window overshoots to `1.25` at `t = 0.5`), which would
+    /// otherwise let `--normalize peak` under-estimate the true post-resample peak
+    /// and clip.
+    fn sample(self, x0: f32, x1: f32, x2: f32, x3: f32, t: f64) -> f32 {
+        let raw = match self {
+            Interp::Nearest => {
+                if t < 0.5 {
+                    x1
+                } else {
+                    x2
+                }
+            }
+            Interp::Linear => (x1 as f64 * (1.0 - t) + x2 as f64 * t) as f32,
+            Interp::Cosine => {
+                let m = (1.0 - (std::f64::consts::PI * t).cos()) / 2.0;
+                (x1 as f64 * (1.0 - m) + x2 as f64 * m) as f32
+            }
+            Interp::Cubic => {
+                let (x0, x1, x2, x3) = (x0 as f64, x1 as f64, x2 as f64, x3 as f64);
+                let c0 = x1;
+                let c1 = 0.5 * (x2 - x0);
+                let c2 = x0 - 2.5 * x1 + 2.0 * x2 - 0.5 * x3;
+                let c3 = 0.5 * (x3 - x0) + 1.5 * (x1 - x2);
+                (((c3 * t + c2) * t + c1) * t + c0) as f32
+            }
+        };
+
+        match self {
+            Interp::Cosine | Interp::Cubic => raw.clamp(x1.min(x2), x1.max(x2)),
+            Interp::Nearest | Interp::Linear => raw,
+        }
+    }
+}
+
+/// Resamples a stereo stream between sample rates, fed one source block at a time so
+/// peak memory stays bounded regardless of song length. Reads before the start or
+/// past the end of the source are clamped to the first/last available sample.
+pub struct Resampler {
+    interp: Interp,
+    /// Source frames per output frame.
+    ratio: f64,
+    /// Continuous position in the source timeline of the next output frame.
+    src_pos: f64,
+    buffer_l: VecDeque<f32>,
+    buffer_r: VecDeque<f32>,
+    /// Source index of `buffer_l[0]`/`buffer_r[0]`.
+    buffer_start: i64,
+    source_done: bool,
+    output_frames_total: u64,
+    frames_emitted: u64,
+}
+
+impl Resampler {
+    pub fn new(interp: Interp, source_rate: u32, target_rate: u32, output_frames_total: u64) -> Self {
+        Self {
+            interp,
+            ratio: source_rate as f64 / target_rate as f64,
+            src_pos: 0.0,
+            buffer_l: VecDeque::new(),
+            buffer_r: VecDeque::new(),
+            buffer_start: 0,
+            source_done: false,
+            output_frames_total,
+            frames_emitted: 0,
+        }
+    }
+
+    /// Feeds one block of source-rate frames and returns the resampled output frames
+    /// that are now available.
+    pub fn push_block(&mut self, left: &[f32], right: &[f32]) -> Vec<(f32, f32)> {
+        for (&l, &r) in left.iter().zip(right) {
+            self.buffer_l.push_back(l);
+            self.buffer_r.push_back(r);
+        }
+        self.drain()
+    }
+
+    /// Call once after the last `push_block`, once the source is fully known to be
+    /// exhausted, to flush any remaining output frames (clamping reads past the end
+    /// of the source to the last sample).
+    pub fn finish(&mut self) -> Vec<(f32, f32)> {
+        self.source_done = true;
+        self.drain()
+    }
+
+    fn drain(&mut self) -> Vec<(f32, f32)> {
+        let mut out = Vec::new();
+
+        while self.frames_emitted < self.output_frames_total {
+            let i = self.src_pos.floor() as i64;
+            let buffer_end = self.buffer_start + self.buffer_l.len() as i64;
+            if i + 2 >= buffer_end && !self.source_done {
+                break;
+            }
+
+            let t = self.src_pos - i as f64;
+            let at = |buffer: &VecDeque<f32>, index: i64| -> f32 {
+                let clamped = index.clamp(self.buffer_start, buffer_end - 1);
+                buffer[(clamped - self.buffer_start) as usize]
+            };
+
+            let l = self.interp.sample(
+                at(&self.buffer_l, i - 1),
+                at(&self.buffer_l, i),
+                at(&self.buffer_l, i + 1),
+                at(&self.buffer_l, i + 2),
+                t,
+            );
+            let r = self.interp.sample(
+                at(&self.buffer_r, i - 1),
+                at(&self.buffer_r, i),
+                at(&self.buffer_r, i + 1),
+                at(&self.buffer_r, i + 2),
+                t,
+            );
+            out.push((l, r));
+
+            self.frames_emitted += 1;
+            self.src_pos += self.ratio;
+        }
+
+        // Drop samples we'll never read again, keeping one sample of left margin.
+        let keep_from = (self.src_pos.floor() as i64 - 1).max(self.buffer_start);
+        while self.buffer_start < keep_from && self.buffer_l.len() > 1 {
+            self.buffer_l.pop_front();
+            self.buffer_r.pop_front();
+            self.buffer_start += 1;
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_picks_the_closer_sample() {
+        assert_eq!(Interp::Nearest.sample(0.0, 1.0, 3.0, 0.0, 0.25), 1.0);
+        assert_eq!(Interp::Nearest.sample(0.0, 1.0, 3.0, 0.0, 0.75), 3.0);
+    }
+
+    #[test]
+    fn linear_interpolates_exactly_at_the_midpoint() {
+        assert_eq!(Interp::Linear.sample(0.0, 0.0, 10.0, 0.0, 0.5), 5.0);
+    }
+
+    #[test]
+    fn cosine_matches_endpoints_and_stays_monotonic() {
+        assert_eq!(Interp::Cosine.sample(0.0, 1.0, 3.0, 0.0, 0.0), 1.0);
+        let mid = Interp::Cosine.sample(0.0, 1.0, 3.0, 0.0, 0.5);
+        assert!((1.0..=3.0).contains(&mid));
+    }
+
+    #[test]
+    fn cubic_overshoot_is_clamped_to_the_interpolated_pair() {
+        // Catmull-Rom through (-1, 1, 1, -1) overshoots to 1.25 at t=0.5 if
+        // unclamped; it must be pulled back to the x1/x2 range (here, exactly 1.0).
+        let mid = Interp::Cubic.sample(-1.0, 1.0, 1.0, -1.0, 0.5);
+        assert_eq!(mid, 1.0);
+    }
+
+    #[test]
+    fn constant_signal_resamples_to_the_same_constant() {
+        for interp in [Interp::Nearest, Interp::Linear, Interp::Cosine, Interp::Cubic] {
+            let mut resampler = Resampler::new(interp, 48_000, 44_100, 20);
+            let source = vec![2.0_f32; 32];
+            let mut out = resampler.push_block(&source, &source);
+            out.extend(resampler.finish());
+
+            assert_eq!(out.len(), 20);
+            for (l, r) in out {
+                assert!((l - 2.0).abs() < 1e-4, "{interp:?} left {l} != 2.0");
+                assert!((r - 2.0).abs() < 1e-4, "{interp:?} right {r} != 2.0");
+            }
+        }
+    }
+
+    #[test]
+    fn upsampling_doubles_the_output_frame_count() {
+        let mut resampler = Resampler::new(Interp::Linear, 1, 2, 8);
+        let source = vec![0.0_f32; 4];
+        let mut out = resampler.push_block(&source, &source);
+        out.extend(resampler.finish());
+        assert_eq!(out.len(), 8);
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    io::{self, Write},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::dither::DitherState;
+
+// See: http://soundfile.sapp.org/doc/WaveFormat/
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+const CHANNEL_MASK_STEREO: u32 = 0x3; // front-left | front-right
+
+// KSDATAFORMAT_SUBTYPE_PCM / KSDATAFORMAT_SUBTYPE_IEEE_FLOAT, as the 16-byte GUID
+// bytes appear on the wire (the first three fields are little-endian).
+const SUBFORMAT_PCM: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+const SUBFORMAT_IEEE_FLOAT: [u8; 16] = [
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// The sample representation used for a rendered WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Quantized integer PCM (8, 16, 24 or 32 bits per sample).
+    Pcm,
+    /// IEEE 754 float (32 or 64 bits per sample), written with no quantization.
+    Float,
+}
+
+impl SampleFormat {
+    /// Whether `bit_depth` is a supported depth for this format.
+    pub fn supports_bit_depth(self, bit_depth: u16) -> bool {
+        match self {
+            SampleFormat::Pcm => matches!(bit_depth, 8 | 16 | 24 | 32),
+            SampleFormat::Float => matches!(bit_depth, 32 | 64),
+        }
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pcm" => Ok(SampleFormat::Pcm),
+            "float" => Ok(SampleFormat::Float),
+            _ => Err(format!("Expected \"pcm\" or \"float\" (found \"{s}\")")),
+        }
+    }
+}
+
+/// User-supplied tags written to the `LIST`/`INFO` chunk. `ISFT` (software) and
+/// `ICRD` (creation date) are always filled in automatically and aren't part of
+/// this struct.
+#[derive(Debug, Clone, Default)]
+pub struct WavMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub comment: Option<String>,
+}
+
+/// A single `LIST`/`INFO` sub-chunk: 4-byte FourCC + 4-byte LE length + NUL-terminated
+/// string, padded to an even number of bytes.
+fn info_subchunk(out: &mut Vec<u8>, four_cc: &[u8; 4], text: &str) {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0); // NUL terminator
+    if !bytes.len().is_multiple_of(2) {
+        bytes.push(0); // pad to a word boundary
+    }
+
+    out.extend_from_slice(four_cc);
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(&bytes);
+}
+
+/// Builds a full `LIST`/`INFO` chunk (FourCC + size + `INFO` + sub-chunks) for
+/// `metadata`. `ICRD` is stamped with today's UTC date and `ISFT` with this crate's
+/// name and version.
+fn info_list_chunk(metadata: &WavMetadata) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(b"INFO");
+
+    info_subchunk(
+        &mut body,
+        b"INAM",
+        metadata.title.as_deref().unwrap_or("Untitled"),
+    );
+    if let Some(artist) = &metadata.artist {
+        info_subchunk(&mut body, b"IART", artist);
+    }
+    if let Some(comment) = &metadata.comment {
+        info_subchunk(&mut body, b"ICMT", comment);
+    }
+    info_subchunk(
+        &mut body,
+        b"ISFT",
+        &format!(
+            "{} {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ),
+    );
+    info_subchunk(&mut body, b"ICRD", &today_as_iso8601());
+
+    let mut chunk = Vec::with_capacity(8 + body.len());
+    chunk.extend_from_slice(b"LIST");
+    chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(&body);
+    chunk
+}
+
+/// Today's UTC date as `YYYY-MM-DD`, derived from the system clock with no calendar
+/// dependency (civil-from-days, per Howard Hinnant's `chrono::civil_from_days`).
+fn today_as_iso8601() -> String {
+    let days_since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock before unix epoch")
+        .as_secs() as i64
+        / 86_400;
+
+    let z = days_since_epoch + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = (z - era * 146_097) as u64;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let year = yoe as i64 + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = if month <= 2 { year + 1 } else { year };
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Writes a canonical WAV header (RIFF/fmt /LIST/data) for `total_frames` stereo
+/// frames at `sample_rate`, `format` and `bit_depth`, leaving the writer positioned
+/// to receive the raw sample bytes written by [`encode_samples`].
+///
+/// 24-bit PCM and all float output use a `WAVE_FORMAT_EXTENSIBLE` `fmt ` chunk, since
+/// plenty of DAWs reject the bare 24-bit layout; everything else uses the plain
+/// `fmt ` chunk. `metadata` is embedded as a `LIST`/`INFO` chunk so the file is
+/// self-describing without needing a sidecar.
+pub fn write_wav_header(
+    writer: &mut impl Write,
+    sample_rate: u32,
+    format: SampleFormat,
+    bit_depth: u16,
+    total_frames: u64,
+    metadata: &WavMetadata,
+) -> io::Result<()> {
+    debug_assert!(
+        format.supports_bit_depth(bit_depth),
+        "Unsupported bit depth {bit_depth} for format {format:?}"
+    );
+    let byte_depth = bit_depth / 8;
+    let data_length = total_frames * 2 * byte_depth as u64;
+    let data_length: u32 = data_length
+        .try_into()
+        .expect("rendered audio is too long to fit in a 32-bit WAV data chunk");
+
+    let extensible = bit_depth == 24 || format == SampleFormat::Float;
+    let fmt_chunk_size: u32 = if extensible { 40 } else { 16 };
+    let list_chunk = info_list_chunk(metadata);
+
+    // RIFF header
+    writer.write_all(b"RIFF")?; // ChunkID
+    writer.write_all(
+        &(4 + (8 + fmt_chunk_size) + list_chunk.len() as u32 + (8 + data_length)).to_le_bytes(),
+    )?; // ChunkSize
+    writer.write_all(b"WAVE")?; // Format
+
+    // subchunk 1: 'fmt '
+    writer.write_all(b"fmt ")?; // Subchunk1ID
+    writer.write_all(&fmt_chunk_size.to_le_bytes())?; // Subchunk1Size
+    writer.write_all(
+        &(if extensible {
+            WAVE_FORMAT_EXTENSIBLE
+        } else if format == SampleFormat::Float {
+            WAVE_FORMAT_IEEE_FLOAT
+        } else {
+            WAVE_FORMAT_PCM
+        })
+        .to_le_bytes(),
+    )?; // AudioFormat
+    writer.write_all(&2u16.to_le_bytes())?; // NumChannels (2 for stereo)
+    writer.write_all(&sample_rate.to_le_bytes())?; // SampleRate
+    writer.write_all(&(sample_rate * 2 * byte_depth as u32).to_le_bytes())?; // ByteRate, SampleRate * NumChannels * ByteDepth
+    writer.write_all(&(2u16 * byte_depth).to_le_bytes())?; // BlockAlign, NumChannels * ByteDepth
+    writer.write_all(&bit_depth.to_le_bytes())?; // BitsPerSample
+
+    if extensible {
+        writer.write_all(&22u16.to_le_bytes())?; // cbSize, size of the extension below
+        writer.write_all(&bit_depth.to_le_bytes())?; // wValidBitsPerSample, no padding since samples are packed tightly
+        writer.write_all(&CHANNEL_MASK_STEREO.to_le_bytes())?; // dwChannelMask
+        writer.write_all(if format == SampleFormat::Float {
+            &SUBFORMAT_IEEE_FLOAT
+        } else {
+            &SUBFORMAT_PCM
+        })?; // SubFormat
+    }
+
+    // subchunk 2: 'LIST' (INFO)
+    writer.write_all(&list_chunk)?;
+
+    // subchunk 3: 'data'
+    writer.write_all(b"data")?;
+    writer.write_all(&data_length.to_le_bytes())?;
+
+    Ok(())
+}
+
+/// Encodes a block of interleaved `(left, right)` samples at `format`/`bit_depth` and
+/// writes them straight to `writer`. Call this once per rendered block after
+/// [`write_wav_header`]; the caller is responsible for writing exactly as many frames
+/// in total as the header's `total_frames`.
+///
+/// `dither` is only consulted for 8- and 16-bit PCM output; pass `None` (as `main` does
+/// for [`Dither::Off`](crate::dither::Dither)) to fall back to plain truncation. 24/32-bit
+/// and float output never dither, since they don't need it.
+pub fn encode_samples(
+    writer: &mut impl Write,
+    samples: impl Iterator<Item = (f32, f32)>,
+    format: SampleFormat,
+    bit_depth: u16,
+    mut dither: Option<&mut DitherState>,
+) -> io::Result<()> {
+    for (l, r) in samples {
+        if format == SampleFormat::Float {
+            match bit_depth {
+                32 => {
+                    writer.write_all(&l.to_le_bytes())?;
+                    writer.write_all(&r.to_le_bytes())?;
+                }
+                64 => {
+                    writer.write_all(&(l as f64).to_le_bytes())?;
+                    writer.write_all(&(r as f64).to_le_bytes())?;
+                }
+                _ => unreachable!("Unexpected float bit depth {bit_depth}, expected 32 or 64"),
+            }
+            continue;
+        }
+
+        // convert to 64-bit float to ensure no accuracy loss
+        let (l, r) = (l as f64, r as f64);
+        match bit_depth {
+            8 => {
+                let (l, r) = ((l + 1.) / 2., (r + 1.) / 2.);
+                let (l, r) = (l * 256., r * 256.);
+                let (l, r) = match &mut dither {
+                    Some(state) => (state.quantize(0, l), state.quantize(1, r)),
+                    None => (l.trunc(), r.trunc()),
+                };
+                writer.write_all(&(l as u8).to_le_bytes())?;
+                writer.write_all(&(r as u8).to_le_bytes())?;
+            }
+            16 => {
+                let (l, r) = (l * 32_767., r * 32_767.);
+                let (l, r) = match &mut dither {
+                    Some(state) => (state.quantize(0, l), state.quantize(1, r)),
+                    None => (l.trunc(), r.trunc()),
+                };
+                writer.write_all(&(l as i16).to_le_bytes())?;
+                writer.write_all(&(r as i16).to_le_bytes())?;
+            }
+            24 => {
+                let convert = |num: i32| {
+                    let bytes = num.to_le_bytes();
+                    let fixed_byte_2 = (bytes[2] & 0b0111_1111) | (bytes[3] & 0b1000_0000);
+                    [bytes[0], bytes[1], fixed_byte_2]
+                };
+
+                writer.write_all(&convert((l * 8_388_607.) as i32))?;
+                writer.write_all(&convert((r * 8_388_607.) as i32))?;
+            }
+            32 => {
+                writer.write_all(&((l * 2_147_483_647.) as i32).to_le_bytes())?;
+                writer.write_all(&((r * 2_147_483_647.) as i32).to_le_bytes())?;
+            }
+            _ => unreachable!("Unexpected PCM bit depth {bit_depth}, expected 8, 16, 24 or 32"),
+        };
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_bytes(format: SampleFormat, bit_depth: u16, total_frames: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_wav_header(
+            &mut out,
+            48_000,
+            format,
+            bit_depth,
+            total_frames,
+            &WavMetadata::default(),
+        )
+        .unwrap();
+        out
+    }
+
+    #[test]
+    fn info_subchunk_pads_an_odd_length_string_to_a_word_boundary() {
+        // "ab" -> NUL terminator makes 3 bytes, which needs one more pad byte.
+        let mut out = Vec::new();
+        info_subchunk(&mut out, b"ICMT", "ab");
+        assert_eq!(&out[0..4], b"ICMT");
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()), 4);
+        assert_eq!(&out[8..12], &[b'a', b'b', 0, 0]);
+    }
+
+    #[test]
+    fn info_subchunk_does_not_pad_an_already_even_length_string() {
+        // "abc" -> NUL terminator makes 4 bytes, already word-aligned.
+        let mut out = Vec::new();
+        info_subchunk(&mut out, b"ICMT", "abc");
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()), 4);
+        assert_eq!(&out[8..12], &[b'a', b'b', b'c', 0]);
+    }
+
+    #[test]
+    fn info_list_chunk_always_carries_title_isft_and_icrd() {
+        let chunk = info_list_chunk(&WavMetadata::default());
+        assert_eq!(&chunk[0..4], b"LIST");
+        assert_eq!(&chunk[8..12], b"INFO");
+        assert!(chunk.windows(4).any(|w| w == b"INAM"));
+        assert!(chunk.windows(4).any(|w| w == b"ISFT"));
+        assert!(chunk.windows(4).any(|w| w == b"ICRD"));
+        assert!(!chunk.windows(4).any(|w| w == b"IART"));
+    }
+
+    #[test]
+    fn info_list_chunk_includes_artist_and_comment_only_when_set() {
+        let metadata = WavMetadata {
+            title: Some("Song".to_string()),
+            artist: Some("Someone".to_string()),
+            comment: None,
+        };
+        let chunk = info_list_chunk(&metadata);
+        assert!(chunk.windows(4).any(|w| w == b"IART"));
+        assert!(!chunk.windows(4).any(|w| w == b"ICMT"));
+    }
+
+    #[test]
+    fn sixteen_bit_pcm_uses_the_plain_16_byte_fmt_chunk() {
+        let header = header_bytes(SampleFormat::Pcm, 16, 0);
+        assert_eq!(u32::from_le_bytes(header[16..20].try_into().unwrap()), 16);
+        assert_eq!(u16::from_le_bytes(header[20..22].try_into().unwrap()), WAVE_FORMAT_PCM);
+    }
+
+    #[test]
+    fn twenty_four_bit_pcm_uses_the_extensible_fmt_chunk_with_the_pcm_subformat() {
+        let header = header_bytes(SampleFormat::Pcm, 24, 0);
+        assert_eq!(u32::from_le_bytes(header[16..20].try_into().unwrap()), 40);
+        assert_eq!(
+            u16::from_le_bytes(header[20..22].try_into().unwrap()),
+            WAVE_FORMAT_EXTENSIBLE
+        );
+        assert_eq!(&header[44..60], &SUBFORMAT_PCM);
+    }
+
+    #[test]
+    fn float_output_uses_the_extensible_fmt_chunk_with_the_float_subformat() {
+        let header = header_bytes(SampleFormat::Float, 32, 0);
+        assert_eq!(
+            u16::from_le_bytes(header[20..22].try_into().unwrap()),
+            WAVE_FORMAT_EXTENSIBLE
+        );
+        assert_eq!(&header[44..60], &SUBFORMAT_IEEE_FLOAT);
+    }
+
+    #[test]
+    fn data_chunk_length_matches_frame_count_times_block_align() {
+        let header = header_bytes(SampleFormat::Pcm, 16, 100);
+        // 16-bit stereo: 4 bytes/frame.
+        let data_pos = header.windows(4).position(|w| w == b"data").unwrap();
+        assert_eq!(
+            u32::from_le_bytes(header[data_pos + 4..data_pos + 8].try_into().unwrap()),
+            400
+        );
+        assert_eq!(header.len(), data_pos + 8);
+    }
+
+    #[test]
+    fn riff_chunk_size_covers_the_whole_file_minus_the_riff_header_itself() {
+        let header = header_bytes(SampleFormat::Pcm, 16, 100);
+        let riff_size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        assert_eq!(riff_size as usize, header.len() - 8 + 400);
+    }
+}